@@ -4,7 +4,10 @@ use collections::HashSet;
 
 use super::{
     super::Result,
-    path_expr::{PathExpression, PathLeg, PATH_EXPR_ARRAY_INDEX_ASTERISK, PATH_EXPR_ASTERISK},
+    path_expr::{
+        ArrayRangeIndex, ComparisonOp, FilterExpr, PathExpression, PathLeg,
+        PATH_EXPR_ARRAY_INDEX_ASTERISK, PATH_EXPR_ASTERISK,
+    },
     Json, JsonRef, JsonType,
 };
 
@@ -15,13 +18,19 @@ impl<'a> JsonRef<'a> {
     /// it returns None.
     ///
     /// See `Extract()` in TiDB `json.binary_function.go`
-    pub fn extract(&self, path_expr_list: &[PathExpression]) -> Result<Option<Json>> {
-        let mut could_return_multiple_matches = path_expr_list.len() > 1;
+    ///
+    /// Accepts either plain `PathExpression`s or cached
+    /// `path_expr::CompiledPath` handles (see `path_expr::compile_path_expr`)
+    /// interchangeably, since both implement `AsRef<PathExpression>`.
+    pub fn extract<P: AsRef<PathExpression>>(&self, path_expr_list: &[P]) -> Result<Option<Json>> {
+        let could_return_multiple_matches = path_expr_list.len() > 1
+            || path_expr_list
+                .iter()
+                .any(|e| e.as_ref().could_return_multiple_matches());
 
-        let mut elem_list = Vec::with_capacity(path_expr_list.len());
-        for path_expr in path_expr_list {
-            could_return_multiple_matches |= path_expr.contains_any_asterisk();
-            elem_list.append(&mut extract_json(*self, &path_expr.legs)?)
+        let mut elem_list = Vec::new();
+        for matched in self.extract_iter(path_expr_list) {
+            elem_list.push(matched?);
         }
         if elem_list.is_empty() {
             Ok(None)
@@ -33,6 +42,163 @@ impl<'a> JsonRef<'a> {
             Ok(Some(elem_list.remove(0).to_owned()))
         }
     }
+
+    /// `extract_iter` is a lazy, allocation-light alternative to `extract`:
+    /// it walks `path_expr_list` over `self` with an explicit work stack
+    /// instead of building a fresh `Vec` at every recursion level, and
+    /// yields matched nodes on demand. Dedup only happens *within* the
+    /// traversal of a single `PathExpression`, matching `extract_json`'s
+    /// semantics: distinct entries of `path_expr_list` are independent and
+    /// may yield the same node more than once (see
+    /// `append_if_ref_unique` for the per-call-allocation version this
+    /// replaces for deep `DoubleAsterisk` walks).
+    pub fn extract_iter<'p, P: AsRef<PathExpression>>(
+        &self,
+        path_expr_list: &'p [P],
+    ) -> ExtractIter<'a, 'p> {
+        let mut stack = Vec::with_capacity(path_expr_list.len());
+        for (idx, path_expr) in path_expr_list.iter().enumerate().rev() {
+            stack.push((*self, path_expr.as_ref().legs.as_slice(), idx));
+        }
+        ExtractIter {
+            stack,
+            seen: (0..path_expr_list.len())
+                .map(|_| HashSet::with_hasher(Default::default()))
+                .collect(),
+        }
+    }
+}
+
+/// See `JsonRef::extract_iter`.
+pub struct ExtractIter<'a, 'p> {
+    stack: Vec<(JsonRef<'a>, &'p [PathLeg], usize)>,
+    // One `HashSet` per entry of the original `path_expr_list`, so dedup
+    // never crosses between independent path expressions.
+    seen: Vec<HashSet<RefEqualJsonWrapper<'a>>>,
+}
+
+impl<'a, 'p> ExtractIter<'a, 'p> {
+    fn push_child(&mut self, j: Result<JsonRef<'a>>, legs: &'p [PathLeg], expr_idx: usize) {
+        // A corrupt/impossible child (e.g. a malformed binary encoding) is
+        // treated as "no match" rather than aborting the whole traversal,
+        // since `Iterator::next` has no channel to propagate an error.
+        if let Ok(j) = j {
+            self.stack.push((j, legs, expr_idx));
+        }
+    }
+}
+
+impl<'a, 'p> Iterator for ExtractIter<'a, 'p> {
+    type Item = Result<JsonRef<'a>>;
+
+    fn next(&mut self) -> Option<Result<JsonRef<'a>>> {
+        while let Some((j, path_legs, expr_idx)) = self.stack.pop() {
+            if path_legs.is_empty() {
+                if self.seen[expr_idx].insert(RefEqualJsonWrapper(j)) {
+                    return Some(Ok(j));
+                }
+                continue;
+            }
+            let (current_leg, sub_path_legs) = (&path_legs[0], &path_legs[1..]);
+            match *current_leg {
+                PathLeg::Index(i) => match j.get_type() {
+                    JsonType::Array => {
+                        let elem_count = j.get_elem_count();
+                        if i == PATH_EXPR_ARRAY_INDEX_ASTERISK {
+                            for k in (0..elem_count).rev() {
+                                self.push_child(j.array_get_elem(k), sub_path_legs, expr_idx);
+                            }
+                        } else if (i as usize) < elem_count {
+                            self.push_child(j.array_get_elem(i as usize), sub_path_legs, expr_idx);
+                        }
+                    }
+                    _ => {
+                        if i as usize == 0 {
+                            self.stack.push((j, sub_path_legs, expr_idx));
+                        }
+                    }
+                },
+                PathLeg::ArrayRange { start, end } => match j.get_type() {
+                    JsonType::Array => {
+                        let elem_count = j.get_elem_count();
+                        if let Some((s, e)) = PathLeg::resolve_array_range(start, end, elem_count)
+                        {
+                            for k in (s..=e).rev() {
+                                self.push_child(j.array_get_elem(k), sub_path_legs, expr_idx);
+                            }
+                        }
+                    }
+                    _ => {
+                        if PathLeg::array_range_covers_scalar(start, end) {
+                            self.stack.push((j, sub_path_legs, expr_idx));
+                        }
+                    }
+                },
+                PathLeg::Filter(ref expr) => match j.get_type() {
+                    JsonType::Array => {
+                        let elem_count = j.get_elem_count();
+                        for k in (0..elem_count).rev() {
+                            match j.array_get_elem(k) {
+                                Ok(elem) => match eval_filter(elem, expr) {
+                                    Ok(true) => self.stack.push((elem, sub_path_legs, expr_idx)),
+                                    Ok(false) => {}
+                                    Err(e) => return Some(Err(e)),
+                                },
+                                Err(_) => {}
+                            }
+                        }
+                    }
+                    _ => match eval_filter(j, expr) {
+                        Ok(true) => self.stack.push((j, sub_path_legs, expr_idx)),
+                        Ok(false) => {}
+                        Err(e) => return Some(Err(e)),
+                    },
+                },
+                PathLeg::KeyRegex(ref re) => {
+                    if j.get_type() == JsonType::Object {
+                        let elem_count = j.get_elem_count();
+                        for i in (0..elem_count).rev() {
+                            let key = j.object_get_key(i);
+                            if re.is_match(&String::from_utf8_lossy(key)) {
+                                self.push_child(j.object_get_val(i), sub_path_legs, expr_idx);
+                            }
+                        }
+                    }
+                }
+                PathLeg::Key(ref key) => {
+                    if j.get_type() == JsonType::Object {
+                        if key == PATH_EXPR_ASTERISK {
+                            let elem_count = j.get_elem_count();
+                            for i in (0..elem_count).rev() {
+                                self.push_child(j.object_get_val(i), sub_path_legs, expr_idx);
+                            }
+                        } else if let Some(idx) = j.object_search_key(key.as_bytes()) {
+                            self.push_child(j.object_get_val(idx), sub_path_legs, expr_idx);
+                        }
+                    }
+                }
+                PathLeg::DoubleAsterisk => {
+                    match j.get_type() {
+                        JsonType::Array => {
+                            let elem_count = j.get_elem_count();
+                            for k in (0..elem_count).rev() {
+                                self.push_child(j.array_get_elem(k), path_legs, expr_idx);
+                            }
+                        }
+                        JsonType::Object => {
+                            let elem_count = j.get_elem_count();
+                            for i in (0..elem_count).rev() {
+                                self.push_child(j.object_get_val(i), path_legs, expr_idx);
+                            }
+                        }
+                        _ => {}
+                    }
+                    self.stack.push((j, sub_path_legs, expr_idx));
+                }
+            }
+        }
+        None
+    }
 }
 
 #[derive(Eq)]
@@ -72,6 +238,36 @@ fn append_if_ref_unique<'a>(elem_list: &mut Vec<JsonRef<'a>>, other: &Vec<JsonRe
     }
 }
 
+/// `eval_filter` decides whether `elem` (the `@` node) satisfies a
+/// `FilterExpr`, resolving relative sub-paths via `extract_json` on `elem`
+/// itself.
+fn eval_filter<'a>(elem: JsonRef<'a>, expr: &FilterExpr) -> Result<bool> {
+    Ok(match expr {
+        FilterExpr::Exists(sub_path) => !extract_json(elem, sub_path)?.is_empty(),
+        FilterExpr::Compare { left, op, right } => {
+            match extract_json(elem, left)?.first() {
+                // "no match" is false for all relational operators.
+                None => false,
+                Some(lhs) => compare_json(*lhs, right, *op),
+            }
+        }
+        FilterExpr::And(l, r) => eval_filter(elem, l)? && eval_filter(elem, r)?,
+        FilterExpr::Or(l, r) => eval_filter(elem, l)? || eval_filter(elem, r)?,
+    })
+}
+
+fn compare_json(lhs: JsonRef<'_>, rhs: &Json, op: ComparisonOp) -> bool {
+    let ordering = lhs.cmp(&rhs.as_ref());
+    match op {
+        ComparisonOp::Eq => ordering.is_eq(),
+        ComparisonOp::Ne => ordering.is_ne(),
+        ComparisonOp::Lt => ordering.is_lt(),
+        ComparisonOp::Le => ordering.is_le(),
+        ComparisonOp::Gt => ordering.is_gt(),
+        ComparisonOp::Ge => ordering.is_ge(),
+    }
+}
+
 /// `extract_json` is used by JSON::extract().
 pub fn extract_json<'a>(j: JsonRef<'a>, path_legs: &[PathLeg]) -> Result<Vec<JsonRef<'a>>> {
     if path_legs.is_empty() {
@@ -103,6 +299,58 @@ pub fn extract_json<'a>(j: JsonRef<'a>, path_legs: &[PathLeg]) -> Result<Vec<Jso
                 }
             }
         },
+        PathLeg::ArrayRange { start, end } => match j.get_type() {
+            JsonType::Array => {
+                let elem_count = j.get_elem_count();
+                if let Some((s, e)) = PathLeg::resolve_array_range(start, end, elem_count) {
+                    for k in s..=e {
+                        append_if_ref_unique(
+                            &mut ret,
+                            &extract_json(j.array_get_elem(k)?, sub_path_legs)?,
+                        )
+                    }
+                }
+            }
+            _ => {
+                // A range over a scalar autowraps it exactly like `Index(0)`
+                // does, as long as the range genuinely covers index 0.
+                if PathLeg::array_range_covers_scalar(start, end) {
+                    append_if_ref_unique(&mut ret, &extract_json(j, sub_path_legs)?)
+                }
+            }
+        },
+        PathLeg::Filter(ref expr) => match j.get_type() {
+            JsonType::Array => {
+                let elem_count = j.get_elem_count();
+                for k in 0..elem_count {
+                    let elem = j.array_get_elem(k)?;
+                    if eval_filter(elem, expr)? {
+                        append_if_ref_unique(&mut ret, &extract_json(elem, sub_path_legs)?)
+                    }
+                }
+            }
+            _ => {
+                // A single object (or scalar) is treated as a one-element
+                // set, same as the array case above with `elem_count == 1`.
+                if eval_filter(j, expr)? {
+                    append_if_ref_unique(&mut ret, &extract_json(j, sub_path_legs)?)
+                }
+            }
+        },
+        PathLeg::KeyRegex(ref re) => {
+            if j.get_type() == JsonType::Object {
+                let elem_count = j.get_elem_count();
+                for i in 0..elem_count {
+                    let key = j.object_get_key(i);
+                    if re.is_match(&String::from_utf8_lossy(key)) {
+                        append_if_ref_unique(
+                            &mut ret,
+                            &extract_json(j.object_get_val(i)?, sub_path_legs)?,
+                        )
+                    }
+                }
+            }
+        }
         PathLeg::Key(ref key) => {
             if j.get_type() == JsonType::Object {
                 if key == PATH_EXPR_ASTERISK {
@@ -151,10 +399,13 @@ pub fn extract_json<'a>(j: JsonRef<'a>, path_legs: &[PathLeg]) -> Result<Vec<Jso
 mod tests {
     use std::str::FromStr;
 
+    use regex::Regex;
+
     use super::{
         super::path_expr::{
-            PathExpressionFlag, PATH_EXPRESSION_CONTAINS_ASTERISK,
-            PATH_EXPRESSION_CONTAINS_DOUBLE_ASTERISK, PATH_EXPR_ARRAY_INDEX_ASTERISK,
+            compile_path_expr, ArrayRangeIndex, ComparisonOp, FilterExpr, PathExpressionFlag,
+            PATH_EXPRESSION_CONTAINS_ASTERISK, PATH_EXPRESSION_CONTAINS_DOUBLE_ASTERISK,
+            PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG, PATH_EXPR_ARRAY_INDEX_ASTERISK,
         },
         *,
     };
@@ -413,6 +664,94 @@ mod tests {
                 }],
                 Some("[1, 1, 1, 1]"),
             ),
+            // Array range
+            (
+                "[0, 1, 2, 3, 4]",
+                vec![PathExpression {
+                    legs: vec![PathLeg::ArrayRange {
+                        start: ArrayRangeIndex::Abs(1),
+                        end: ArrayRangeIndex::Abs(3),
+                    }],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some("[1, 2, 3]"),
+            ),
+            (
+                "[0, 1, 2, 3, 4]",
+                vec![PathExpression {
+                    legs: vec![PathLeg::ArrayRange {
+                        start: ArrayRangeIndex::LastOffset(2),
+                        end: ArrayRangeIndex::LastOffset(0),
+                    }],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some("[2, 3, 4]"),
+            ),
+            (
+                "[0, 1, 2]",
+                vec![PathExpression {
+                    legs: vec![PathLeg::ArrayRange {
+                        start: ArrayRangeIndex::Abs(2),
+                        end: ArrayRangeIndex::Abs(0),
+                    }],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                None,
+            ),
+            (
+                "true",
+                vec![PathExpression {
+                    legs: vec![PathLeg::ArrayRange {
+                        start: ArrayRangeIndex::Abs(0),
+                        end: ArrayRangeIndex::LastOffset(0),
+                    }],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some("[true]"),
+            ),
+            (
+                "true",
+                vec![PathExpression {
+                    legs: vec![PathLeg::ArrayRange {
+                        start: ArrayRangeIndex::Abs(5),
+                        end: ArrayRangeIndex::Abs(10),
+                    }],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                None,
+            ),
+            // Filter
+            (
+                r#"[{"price": 5}, {"price": 20}, {"price": 8}]"#,
+                vec![PathExpression {
+                    legs: vec![PathLeg::Filter(FilterExpr::Compare {
+                        left: vec![PathLeg::Key(String::from("price"))],
+                        op: ComparisonOp::Lt,
+                        right: Json::from_str("10").unwrap(),
+                    })],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some(r#"[{"price": 5}, {"price": 8}]"#),
+            ),
+            (
+                r#"[{"a": 1}, {"b": 2}]"#,
+                vec![PathExpression {
+                    legs: vec![PathLeg::Filter(FilterExpr::Exists(vec![PathLeg::Key(
+                        String::from("a"),
+                    )]))],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some(r#"[{"a": 1}]"#),
+            ),
+            // Key regex
+            (
+                r#"{"a1": 1, "a2": 2, "b1": 3}"#,
+                vec![PathExpression {
+                    legs: vec![PathLeg::KeyRegex(Regex::new("^a").unwrap())],
+                    flags: PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG,
+                }],
+                Some("[1, 2]"),
+            ),
         ];
         for (i, (js, exprs, expected)) in test_cases.drain(..).enumerate() {
             let j = js.parse();
@@ -438,4 +777,12 @@ mod tests {
             );
         }
     }
+
+    #[test]
+    fn test_json_extract_with_compiled_path() {
+        let j: Json = r#"{"a": {"b": 1}}"#.parse().unwrap();
+        let compiled = compile_path_expr("$.a.b").unwrap();
+        let got = j.as_ref().extract(&[compiled]).unwrap().unwrap();
+        assert_eq!(got.to_string(), "1");
+    }
 }
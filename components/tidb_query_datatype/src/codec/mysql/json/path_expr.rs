@@ -0,0 +1,571 @@
+// Copyright 2017 TiKV Project Authors. Licensed under Apache-2.0.
+
+//! This module parses JSON path expressions (e.g. `$.a[0].b`) into a
+//! sequence of `PathLeg`s that `extract_json` walks over a `JsonRef`.
+//!
+//! See `https://dev.mysql.com/doc/refman/8.0/en/json.html#json-path-syntax`.
+
+use std::sync::Mutex;
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use tikv_util::lru::LruCache;
+
+use super::{
+    super::{Error, Result},
+    Json,
+};
+
+pub const PATH_EXPR_ASTERISK: &str = "*";
+
+// [a-zA-Z_$][a-zA-Z0-9_$]* matches any identifier.
+// "[^"\\]*(\\.[^"\\]*)*" matches any string literal which can carry escaped
+// quotes.
+const PATH_EXPR_LEG_RE_STR: &str = r#"(?:\.\s*([a-zA-Z_$][a-zA-Z0-9_$]*|\*|"[^"\\]*(?:\\.[^"\\]*)*")|(\[\s*([0-9]+|\*|last(?:\s*-\s*[0-9]+)?)\s*(?:to\s*([0-9]+|last(?:\s*-\s*[0-9]+)?)\s*)?\])|(\*\*))"#;
+
+static PATH_EXPR_LEG_RE: Lazy<Regex> = Lazy::new(|| Regex::new(PATH_EXPR_LEG_RE_STR).unwrap());
+
+/// `PATH_EXPR_ARRAY_INDEX_ASTERISK` is used to Represent `*` in array
+/// selection.
+pub const PATH_EXPR_ARRAY_INDEX_ASTERISK: i32 = -1;
+
+/// One endpoint of an `[M to N]` array range selector. Each endpoint is
+/// either an absolute, zero-based index or an offset relative to `last`
+/// (MySQL 8.0.21's `last - N`, with `last` itself being `LastOffset(0)`).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArrayRangeIndex {
+    Abs(i64),
+    LastOffset(i64),
+}
+
+impl ArrayRangeIndex {
+    /// Resolves this endpoint against an array of `elem_count` elements and
+    /// clamps the result into `[0, elem_count - 1]`.
+    fn resolve(self, elem_count: usize) -> i64 {
+        self.resolve_unclamped(elem_count)
+            .clamp(0, elem_count as i64 - 1)
+    }
+
+    /// Resolves this endpoint against an array of `elem_count` elements
+    /// without clamping into `[0, elem_count - 1]`. Used where the caller
+    /// needs to tell a value that genuinely lands in range from one that's
+    /// only in range because it got clamped there — clamping `elem_count`
+    /// itself down to `1` to probe whether a range covers a scalar's
+    /// single-element pseudo-array would otherwise force every endpoint to
+    /// `0`, making any range spuriously "cover" it.
+    fn resolve_unclamped(self, elem_count: usize) -> i64 {
+        let elem_count = elem_count as i64;
+        match self {
+            ArrayRangeIndex::Abs(i) => i,
+            ArrayRangeIndex::LastOffset(offset) => elem_count - 1 - offset,
+        }
+    }
+}
+
+/// `ComparisonOp` is a relational operator used by `FilterExpr::Compare`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// `FilterExpr` is the boolean predicate AST behind a `[?(...)]` path leg.
+///
+/// The left operand of a `Compare` is a sub-path rooted at the element
+/// currently under test (i.e. `@`), resolved by re-running `extract_json`
+/// on that element. The right operand is always a JSON literal.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FilterExpr {
+    /// `[?(@.sub_path)]`: true iff the sub-path yields at least one node.
+    Exists(Vec<PathLeg>),
+    /// `[?(@.sub_path op literal)]`.
+    Compare {
+        left: Vec<PathLeg>,
+        op: ComparisonOp,
+        right: Json,
+    },
+    And(Box<FilterExpr>, Box<FilterExpr>),
+    Or(Box<FilterExpr>, Box<FilterExpr>),
+}
+
+/// `PathLeg` is the path leg in a JSON path expression.
+#[derive(Clone, Debug)]
+pub enum PathLeg {
+    /// `Index` indicates the path leg with step `[number]`, below is its
+    /// `i32` value.
+    Index(i32),
+    /// `Key` indicates the path leg with step `.key`, below is its `String`
+    /// value.
+    Key(String),
+    /// `ArrayRange` indicates the path leg with step `[M to N]`, resolving
+    /// both ends against the matched array's element count.
+    ArrayRange {
+        start: ArrayRangeIndex,
+        end: ArrayRangeIndex,
+    },
+    /// `Filter` indicates the path leg with step `[?(...)]`.
+    Filter(FilterExpr),
+    /// `KeyRegex` indicates the path leg with step `.~/pattern/`, matching
+    /// any object member whose key matches the compiled pattern. The
+    /// pattern is compiled once, when the owning `PathExpression` is
+    /// parsed, so repeated `extract` calls never recompile it.
+    KeyRegex(Regex),
+    /// `DoubleAsterisk` indicates the path leg with step `**`.
+    DoubleAsterisk,
+}
+
+impl PartialEq for PathLeg {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (PathLeg::Index(a), PathLeg::Index(b)) => a == b,
+            (PathLeg::Key(a), PathLeg::Key(b)) => a == b,
+            (
+                PathLeg::ArrayRange {
+                    start: s1,
+                    end: e1,
+                },
+                PathLeg::ArrayRange {
+                    start: s2,
+                    end: e2,
+                },
+            ) => s1 == s2 && e1 == e2,
+            (PathLeg::Filter(a), PathLeg::Filter(b)) => a == b,
+            // `regex::Regex` has no meaningful equality beyond its source
+            // pattern, which is exactly what we want to compare here.
+            (PathLeg::KeyRegex(a), PathLeg::KeyRegex(b)) => a.as_str() == b.as_str(),
+            (PathLeg::DoubleAsterisk, PathLeg::DoubleAsterisk) => true,
+            _ => false,
+        }
+    }
+}
+
+impl PathLeg {
+    /// Resolves an `ArrayRange` leg's endpoints against `elem_count`,
+    /// returning the inclusive `(start, end)` bounds to iterate, or `None`
+    /// if the clamped range is empty/reversed.
+    pub fn resolve_array_range(
+        start: ArrayRangeIndex,
+        end: ArrayRangeIndex,
+        elem_count: usize,
+    ) -> Option<(usize, usize)> {
+        if elem_count == 0 {
+            return None;
+        }
+        let start = start.resolve(elem_count);
+        let end = end.resolve(elem_count);
+        if start > end {
+            return None;
+        }
+        Some((start as usize, end as usize))
+    }
+
+    /// True if an `ArrayRange` leg with these endpoints covers index `0` of
+    /// a non-array scalar's single-element pseudo-array, so it should
+    /// autowrap the scalar into a one-element result just like `Index(0)`
+    /// would. Resolves both endpoints unclamped: clamping them against the
+    /// pseudo-array's single slot first (as `resolve_array_range` does for
+    /// real arrays) would force every endpoint to `0`, making any range —
+    /// however far out of bounds — spuriously match.
+    pub fn array_range_covers_scalar(start: ArrayRangeIndex, end: ArrayRangeIndex) -> bool {
+        let start = start.resolve_unclamped(1);
+        let end = end.resolve_unclamped(1);
+        start <= 0 && end >= 0
+    }
+}
+
+/// `PathExpressionFlag` holds bit flags describing properties of a
+/// `PathExpression` that are cheap to precompute once at parse time.
+pub type PathExpressionFlag = u8;
+
+pub const PATH_EXPRESSION_CONTAINS_ASTERISK: PathExpressionFlag = 0x01;
+pub const PATH_EXPRESSION_CONTAINS_DOUBLE_ASTERISK: PathExpressionFlag = 0x02;
+/// Set for any leg kind that can, on its own, match more than one element of
+/// a single array/object (`ArrayRange`, `Filter`, `KeyRegex`), so that
+/// `JsonRef::extract` knows to autowrap the result into an array instead of
+/// returning just the first match.
+pub const PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG: PathExpressionFlag = 0x04;
+
+/// `PathExpression` is for JSON path expression.
+#[derive(Clone, Debug, PartialEq, Default)]
+pub struct PathExpression {
+    pub legs: Vec<PathLeg>,
+    pub flags: PathExpressionFlag,
+}
+
+impl PathExpression {
+    pub fn contains_any_asterisk(&self) -> bool {
+        (self.flags
+            & (PATH_EXPRESSION_CONTAINS_ASTERISK | PATH_EXPRESSION_CONTAINS_DOUBLE_ASTERISK))
+            != 0
+    }
+
+    /// True if this expression can match more than one node by itself (e.g.
+    /// `*`, `**`, `[M to N]`, `[?(...)]`, `.~/re/`), so the caller must
+    /// autowrap a single match into an array rather than unwrap it.
+    pub fn could_return_multiple_matches(&self) -> bool {
+        self.contains_any_asterisk() || (self.flags & PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG) != 0
+    }
+}
+
+fn parse_array_range_index(s: &str) -> ArrayRangeIndex {
+    let s = s.trim();
+    if let Some(rest) = s.strip_prefix("last") {
+        let rest = rest.trim();
+        if rest.is_empty() {
+            return ArrayRangeIndex::LastOffset(0);
+        }
+        // rest looks like "- N"
+        let offset: i64 = rest.trim_start_matches('-').trim().parse().unwrap_or(0);
+        ArrayRangeIndex::LastOffset(offset)
+    } else if s == PATH_EXPR_ASTERISK {
+        ArrayRangeIndex::Abs(0)
+    } else {
+        ArrayRangeIndex::Abs(s.parse().unwrap_or(0))
+    }
+}
+
+/// `parse_json_path_expr` parses a JSON path expression (e.g. `$.a[1 to
+/// last]`) into a `PathExpression`.
+pub fn parse_json_path_expr(path_expr: &str) -> Result<PathExpression> {
+    // Find the position of first '$'. If there is any no-blank char in front
+    // of it, it's an invalid path expression.
+    let mut s = path_expr.trim_start();
+    if !s.starts_with('$') {
+        return Err(Error::InvalidJsonPath(path_expr.to_owned()));
+    }
+    s = &s[1..];
+
+    let mut legs = vec![];
+    let mut flags = PathExpressionFlag::default();
+    while !s.trim_start().is_empty() {
+        let trimmed = s.trim_start();
+
+        if let Some(body) = trimmed.strip_prefix("[?(") {
+            let end = body
+                .find(")]")
+                .ok_or_else(|| Error::InvalidJsonPath(path_expr.to_owned()))?;
+            let (expr, _) = parse_filter_expr(&body[..end])?;
+            flags |= PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG;
+            legs.push(PathLeg::Filter(expr));
+            s = &trimmed["[?(".len() + end + ")]".len()..];
+            continue;
+        }
+
+        if let Some(body) = trimmed.strip_prefix(".~/") {
+            let end = body
+                .find('/')
+                .ok_or_else(|| Error::InvalidJsonPath(path_expr.to_owned()))?;
+            let re = Regex::new(&body[..end])
+                .map_err(|_| Error::InvalidJsonPath(path_expr.to_owned()))?;
+            flags |= PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG;
+            legs.push(PathLeg::KeyRegex(re));
+            s = &trimmed[".~/".len() + end + 1..];
+            continue;
+        }
+
+        let cap = match PATH_EXPR_LEG_RE.captures(trimmed) {
+            Some(cap) if cap.get(0).unwrap().start() == 0 => cap,
+            _ => return Err(Error::InvalidJsonPath(path_expr.to_owned())),
+        };
+        let whole = cap.get(0).unwrap();
+
+        if let Some(key) = cap.get(1) {
+            let key = key.as_str();
+            if key == PATH_EXPR_ASTERISK {
+                flags |= PATH_EXPRESSION_CONTAINS_ASTERISK;
+                legs.push(PathLeg::Key(String::from(PATH_EXPR_ASTERISK)));
+            } else {
+                let key = if key.starts_with('"') {
+                    key[1..key.len() - 1].replace("\\\"", "\"")
+                } else {
+                    key.to_owned()
+                };
+                legs.push(PathLeg::Key(key));
+            }
+        } else if cap.get(2).is_some() {
+            let start_str = cap.get(3).unwrap().as_str();
+            if let Some(end_str) = cap.get(4) {
+                let start = parse_array_range_index(start_str);
+                let end = parse_array_range_index(end_str.as_str());
+                flags |= PATH_EXPRESSION_CONTAINS_MULTI_MATCH_LEG;
+                legs.push(PathLeg::ArrayRange { start, end });
+            } else if start_str == PATH_EXPR_ASTERISK {
+                flags |= PATH_EXPRESSION_CONTAINS_ASTERISK;
+                legs.push(PathLeg::Index(PATH_EXPR_ARRAY_INDEX_ASTERISK));
+            } else if start_str == "last" || start_str.starts_with("last") {
+                // A bare `last`/`last-N` (no `to`) selects a single index,
+                // same as a plain `[N]` leg, so it must not set the
+                // multi-match flag: `$[last]` should unwrap its one match
+                // just like `$[2]` does, not autowrap it into an array.
+                let idx = parse_array_range_index(start_str);
+                legs.push(PathLeg::ArrayRange {
+                    start: idx,
+                    end: idx,
+                });
+            } else {
+                legs.push(PathLeg::Index(start_str.parse().unwrap_or(0)));
+            }
+        } else if cap.get(5).is_some() {
+            flags |= PATH_EXPRESSION_CONTAINS_DOUBLE_ASTERISK;
+            legs.push(PathLeg::DoubleAsterisk);
+        }
+
+        s = &trimmed[whole.end()..];
+    }
+
+    if let Some(PathLeg::DoubleAsterisk) = legs.last() {
+        return Err(Error::InvalidJsonPath(path_expr.to_owned()));
+    }
+
+    Ok(PathExpression { legs, flags })
+}
+
+/// Parses the relative sub-path following `@` in a filter predicate, e.g.
+/// the `.price` in `@.price < 10`. Only dotted key legs are supported,
+/// which covers the `@.key` shape filter predicates are written against.
+fn parse_relative_subpath(s: &str) -> (Vec<PathLeg>, &str) {
+    let mut legs = vec![];
+    let mut rest = s;
+    while let Some(stripped) = rest.strip_prefix('.') {
+        let ident_len = stripped
+            .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '$'))
+            .unwrap_or(stripped.len());
+        if ident_len == 0 {
+            break;
+        }
+        let (ident, remainder) = stripped.split_at(ident_len);
+        legs.push(PathLeg::Key(ident.to_owned()));
+        rest = remainder;
+    }
+    (legs, rest)
+}
+
+/// A small recursive-descent parser for the boolean predicate inside a
+/// `[?(...)]` filter leg.
+struct FilterParser<'a> {
+    s: &'a str,
+    pos: usize,
+}
+
+impl<'a> FilterParser<'a> {
+    fn new(s: &'a str) -> Self {
+        FilterParser { s, pos: 0 }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.s[self.pos..]
+    }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.s.len() - trimmed.len();
+    }
+
+    fn consume(&mut self, tok: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(tok) {
+            self.pos += tok.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn err(&self) -> Error {
+        Error::InvalidJsonPath(self.s.to_owned())
+    }
+
+    fn parse_or(&mut self) -> Result<FilterExpr> {
+        let mut left = self.parse_and()?;
+        while self.consume("||") {
+            let right = self.parse_and()?;
+            left = FilterExpr::Or(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<FilterExpr> {
+        let mut left = self.parse_primary()?;
+        while self.consume("&&") {
+            let right = self.parse_primary()?;
+            left = FilterExpr::And(Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn parse_primary(&mut self) -> Result<FilterExpr> {
+        if self.consume("(") {
+            let inner = self.parse_or()?;
+            if !self.consume(")") {
+                return Err(self.err());
+            }
+            return Ok(inner);
+        }
+
+        if !self.consume("@") {
+            return Err(self.err());
+        }
+        let (legs, remainder) = parse_relative_subpath(self.rest());
+        self.pos += self.rest().len() - remainder.len();
+        self.skip_ws();
+
+        const OPS: &[(&str, ComparisonOp)] = &[
+            ("==", ComparisonOp::Eq),
+            ("!=", ComparisonOp::Ne),
+            ("<=", ComparisonOp::Le),
+            (">=", ComparisonOp::Ge),
+            ("<", ComparisonOp::Lt),
+            (">", ComparisonOp::Gt),
+        ];
+        for (tok, op) in OPS {
+            if self.consume(tok) {
+                self.skip_ws();
+                let lit_len = self
+                    .rest()
+                    .find(|c: char| c == ')' || c == '&' || c == '|')
+                    .unwrap_or_else(|| self.rest().len());
+                let literal = self.rest()[..lit_len].trim();
+                self.pos += lit_len;
+                let right: Json = literal.parse().map_err(|_| self.err())?;
+                return Ok(FilterExpr::Compare {
+                    left: legs,
+                    op: *op,
+                    right,
+                });
+            }
+        }
+        Ok(FilterExpr::Exists(legs))
+    }
+}
+
+/// Parses a `[?(...)]` filter body (without the surrounding `[?(` `)]`)
+/// into a `FilterExpr`, returning the number of bytes consumed.
+fn parse_filter_expr(s: &str) -> Result<(FilterExpr, usize)> {
+    let mut parser = FilterParser::new(s);
+    let expr = parser.parse_or()?;
+    parser.skip_ws();
+    if !parser.rest().is_empty() {
+        return Err(parser.err());
+    }
+    Ok((expr, parser.pos))
+}
+
+/// A parsed `PathExpression` that was (or can be) shared out of the
+/// process-wide compile cache. `JsonRef::extract`/`extract_iter` accept
+/// `CompiledPath` directly alongside plain `PathExpression`s via
+/// `AsRef<PathExpression>`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompiledPath(PathExpression);
+
+impl AsRef<PathExpression> for CompiledPath {
+    fn as_ref(&self) -> &PathExpression {
+        &self.0
+    }
+}
+
+impl AsRef<PathExpression> for PathExpression {
+    fn as_ref(&self) -> &PathExpression {
+        self
+    }
+}
+
+// The coprocessor evaluates the same handful of path strings against every
+// row of a chunk, so caching the parsed `PathExpression` (and, transitively,
+// any compiled `KeyRegex` legs) keyed by the raw source string avoids
+// re-parsing on each row.
+const PATH_EXPR_CACHE_CAPACITY: usize = 512;
+
+static PATH_EXPR_CACHE: Lazy<Mutex<LruCache<String, CompiledPath>>> =
+    Lazy::new(|| Mutex::new(LruCache::new(PATH_EXPR_CACHE_CAPACITY)));
+
+/// Parses `path_expr`, reusing a cached `CompiledPath` if this exact source
+/// string was compiled before.
+pub fn compile_path_expr(path_expr: &str) -> Result<CompiledPath> {
+    let key = path_expr.to_owned();
+    let mut cache = PATH_EXPR_CACHE.lock().unwrap();
+    if let Some(compiled) = cache.get(&key) {
+        return Ok(compiled.clone());
+    }
+    let compiled = CompiledPath(parse_json_path_expr(path_expr)?);
+    cache.insert(key, compiled.clone());
+    Ok(compiled)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    #[test]
+    fn test_parse_json_path_expr() {
+        let expr = parse_json_path_expr("$.a[1 to last].b").unwrap();
+        assert_eq!(
+            expr.legs,
+            vec![
+                PathLeg::Key(String::from("a")),
+                PathLeg::ArrayRange {
+                    start: ArrayRangeIndex::Abs(1),
+                    end: ArrayRangeIndex::LastOffset(0),
+                },
+                PathLeg::Key(String::from("b")),
+            ]
+        );
+        assert!(expr.could_return_multiple_matches());
+    }
+
+    #[test]
+    fn test_parse_json_path_expr_bare_last_is_single_match() {
+        let expr = parse_json_path_expr("$[last]").unwrap();
+        assert_eq!(
+            expr.legs,
+            vec![PathLeg::ArrayRange {
+                start: ArrayRangeIndex::LastOffset(0),
+                end: ArrayRangeIndex::LastOffset(0),
+            }]
+        );
+        assert!(!expr.could_return_multiple_matches());
+    }
+
+    #[test]
+    fn test_parse_json_path_expr_filter_leg() {
+        let expr = parse_json_path_expr("$.a[?(@.price < 10)]").unwrap();
+        assert_eq!(
+            expr.legs,
+            vec![
+                PathLeg::Key(String::from("a")),
+                PathLeg::Filter(FilterExpr::Compare {
+                    left: vec![PathLeg::Key(String::from("price"))],
+                    op: ComparisonOp::Lt,
+                    right: Json::from_str("10").unwrap(),
+                }),
+            ]
+        );
+        assert!(expr.could_return_multiple_matches());
+    }
+
+    #[test]
+    fn test_parse_json_path_expr_key_regex_leg() {
+        let expr = parse_json_path_expr("$.~/^a/").unwrap();
+        assert_eq!(expr.legs, vec![PathLeg::KeyRegex(Regex::new("^a").unwrap())]);
+        assert!(expr.could_return_multiple_matches());
+    }
+
+    #[test]
+    fn test_compile_path_expr_caches() {
+        let a = compile_path_expr("$.a.b").unwrap();
+        let b = compile_path_expr("$.a.b").unwrap();
+        assert_eq!(a.as_ref(), b.as_ref());
+        assert!(
+            PATH_EXPR_CACHE
+                .lock()
+                .unwrap()
+                .get(&String::from("$.a.b"))
+                .is_some()
+        );
+    }
+}
@@ -1,9 +1,12 @@
 // Copyright 2022 TiKV Project Authors. Licensed under Apache-2.0.
 
-use std::{path::Path, sync::Arc};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use engine_rocks::{
-    raw::{Cache, Env},
+    raw::{Cache, ColumnFamilyDescriptor, DBPath, Env, DB},
     CompactedEventSender, CompactionListener, FlowListener, RocksCfOptions, RocksCompactionJobInfo,
     RocksDbOptions, RocksEngine, RocksEventListener, RocksPersistenceListener, RocksStatistics,
 };
@@ -20,6 +23,25 @@ use crate::{
     storage::config::EngineType,
 };
 
+/// Selects the storage backend `KvEngineFactory` opens tablets against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EngineBackend {
+    /// The production backend: tablets are persisted to disk under the
+    /// builder's configured `env`.
+    RocksDb,
+    /// A volatile backend built on RocksDB's own `Env::mem_env()`. Tablets
+    /// opened against it never touch disk and vanish once destroyed or
+    /// dropped, saving the filesystem I/O a real `RocksDb` backend pays.
+    ///
+    /// This reuses the existing `TabletFactory<RocksEngine>` implementation
+    /// with only the `Env` swapped; there is no separate in-memory
+    /// `KvEngine` keyed by `(ctx.id, ctx.suffix)`. Every tablet still goes
+    /// through RocksDB's own `DB::Open`/`Close`, CF option construction, and
+    /// WAL/SST machinery; this backend removes only the disk I/O, not that
+    /// overhead.
+    Memory,
+}
+
 struct FactoryInner {
     env: Arc<Env>,
     region_info_accessor: Option<RegionInfoAccessor>,
@@ -31,6 +53,11 @@ struct FactoryInner {
     statistics: Arc<RocksStatistics>,
     state_storage: Option<Arc<dyn StateStorage>>,
     lite: bool,
+    // Extra directories, each paired with a target byte capacity, that
+    // RocksDB spreads later/larger SST files into once earlier paths fill
+    // up. Empty means everything lives under the tablet's own directory.
+    db_paths: Vec<(PathBuf, u64)>,
+    backend: EngineBackend,
 }
 
 pub struct KvEngineFactoryBuilder {
@@ -53,6 +80,8 @@ impl KvEngineFactoryBuilder {
                 statistics,
                 state_storage: None,
                 lite: false,
+                db_paths: vec![],
+                backend: EngineBackend::RocksDb,
             },
             compact_event_sender: None,
         }
@@ -96,9 +125,39 @@ impl KvEngineFactoryBuilder {
         self
     }
 
+    /// Extra directories to spread a tablet's SSTs across, each paired with
+    /// a target byte capacity. RocksDB fills configured paths in order,
+    /// placing later/larger files in later paths (e.g. hot levels on NVMe,
+    /// cold levels on HDD).
+    pub fn db_paths(mut self, db_paths: Vec<(PathBuf, u64)>) -> Self {
+        self.inner.db_paths = db_paths;
+        self
+    }
+
+    /// Select the storage backend tablets built by this factory are opened
+    /// against. See `EngineBackend`.
+    pub fn backend(mut self, backend: EngineBackend) -> Self {
+        self.inner.backend = backend;
+        self
+    }
+
     pub fn build(self) -> KvEngineFactory {
+        let mut inner = self.inner;
+        if inner.backend == EngineBackend::Memory {
+            // Silently falling back to the configured (disk-backed) `env`
+            // here would mean a caller that explicitly asked for an
+            // ephemeral/memory factory gets a disk-backed one instead,
+            // without ever being told. That's a correctness trap, not a
+            // degraded-but-safe fallback, so treat it like any other
+            // engine-bootstrap failure in this codebase: fail fast.
+            inner.env = Arc::new(
+                Env::mem_env().unwrap_or_else(|e| {
+                    panic!("failed to create in-memory rocksdb env: {:?}", e)
+                }),
+            );
+        }
         KvEngineFactory {
-            inner: Arc::new(self.inner),
+            inner: Arc::new(inner),
             compact_event_sender: self.compact_event_sender.clone(),
         }
     }
@@ -137,14 +196,27 @@ impl KvEngineFactory {
         self.inner.statistics.clone()
     }
 
-    fn db_opts(&self) -> RocksDbOptions {
+    /// Builds the `RocksDbOptions` a tablet rooted at `path` should be
+    /// opened with.
+    ///
+    /// When `read_only` is set, skips installing the event and compaction
+    /// listeners, which assume exclusive write ownership of the DB
+    /// (`open_tablet_impl` skips the flow and persistence listeners the same
+    /// way). Read-only callers attach to a tablet directory that the
+    /// serving process may already have open, so they must not compete for
+    /// those side effects.
+    fn db_opts(&self, path: &Path, read_only: bool) -> RocksDbOptions {
         // Create kv engine.
         let mut db_opts = self
             .inner
             .rocksdb_config
             .build_opt(Some(self.inner.statistics.as_ref()));
         db_opts.set_env(self.inner.env.clone());
-        if !self.inner.lite {
+        let db_paths = self.resolved_db_paths(path);
+        if !db_paths.is_empty() {
+            db_opts.set_db_paths(&db_paths);
+        }
+        if !self.inner.lite && !read_only {
             db_opts.add_event_listener(RocksEventListener::new(
                 "kv",
                 self.inner.sst_recovery_sender.clone(),
@@ -156,6 +228,44 @@ impl KvEngineFactory {
         db_opts
     }
 
+    /// Resolves each configured `db_paths` directory against `path`'s own
+    /// file name, so every tablet gets its own isolated subdirectory on
+    /// each extra disk instead of sharing one.
+    fn resolved_db_paths(&self, path: &Path) -> Vec<DBPath> {
+        self.resolve_db_paths(path)
+            .into_iter()
+            .filter_map(|(resolved, target_size)| match DBPath::new(&resolved, target_size) {
+                Ok(db_path) => Some(db_path),
+                Err(e) => {
+                    warn!("invalid db_paths entry, skipping";
+                        "path" => %resolved.display(), "err" => ?e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Pure path-resolution half of `resolved_db_paths`, split out so the
+    /// per-tablet subdirectory logic is unit-testable without constructing
+    /// real `DBPath`s.
+    fn resolve_db_paths(&self, path: &Path) -> Vec<(PathBuf, u64)> {
+        if self.inner.db_paths.is_empty() {
+            return vec![];
+        }
+        let suffix = path.file_name();
+        self.inner
+            .db_paths
+            .iter()
+            .map(|(dir, target_size)| {
+                let resolved = match suffix {
+                    Some(suffix) => dir.join(suffix),
+                    None => dir.clone(),
+                };
+                (resolved, *target_size)
+            })
+            .collect()
+    }
+
     fn cf_opts(&self, for_engine: EngineType) -> Vec<(&str, RocksCfOptions)> {
         self.inner.rocksdb_config.build_cf_opts(
             &self.inner.block_cache,
@@ -174,12 +284,12 @@ impl KvEngineFactory {
     /// It will always create in path/DEFAULT_DB_SUB_DIR.
     pub fn create_shared_db(&self, path: impl AsRef<Path>) -> Result<RocksEngine> {
         let path = path.as_ref();
-        let mut db_opts = self.db_opts();
+        let target_path = path.join(DEFAULT_ROCKSDB_SUB_DIR);
+        let mut db_opts = self.db_opts(&target_path, false);
         let cf_opts = self.cf_opts(EngineType::RaftKv);
         if let Some(listener) = &self.inner.flow_listener {
             db_opts.add_event_listener(listener.clone());
         }
-        let target_path = path.join(DEFAULT_ROCKSDB_SUB_DIR);
         let kv_engine =
             engine_rocks::util::new_engine_opt(target_path.to_str().unwrap(), db_opts, cf_opts);
         if let Err(e) = &kv_engine {
@@ -189,45 +299,105 @@ impl KvEngineFactory {
     }
 }
 
-impl TabletFactory<RocksEngine> for KvEngineFactory {
-    fn open_tablet(&self, ctx: TabletContext, path: &Path) -> Result<RocksEngine> {
-        let mut db_opts = self.db_opts();
+impl KvEngineFactory {
+    fn open_tablet_impl(
+        &self,
+        ctx: TabletContext,
+        path: &Path,
+        read_only: bool,
+    ) -> Result<RocksEngine> {
+        let mut db_opts = self.db_opts(path, read_only);
         let cf_opts = self.cf_opts(EngineType::RaftKv2);
-        if let Some(listener) = &self.inner.flow_listener && let Some(suffix) = ctx.suffix {
-            db_opts.add_event_listener(listener.clone_with(ctx.id, suffix));
-        }
-        if let Some(storage) = &self.inner.state_storage
-            && let Some(flush_state) = ctx.flush_state {
-            let listener = PersistenceListener::new(
-                ctx.id,
-                ctx.suffix.unwrap(),
-                flush_state,
-                storage.clone(),
-            );
-            db_opts.add_event_listener(RocksPersistenceListener::new(listener));
+        if !read_only {
+            if let Some(listener) = &self.inner.flow_listener && let Some(suffix) = ctx.suffix {
+                db_opts.add_event_listener(listener.clone_with(ctx.id, suffix));
+            }
+            if let Some(storage) = &self.inner.state_storage
+                && let Some(flush_state) = ctx.flush_state {
+                let listener = PersistenceListener::new(
+                    ctx.id,
+                    ctx.suffix.unwrap(),
+                    flush_state,
+                    storage.clone(),
+                );
+                db_opts.add_event_listener(RocksPersistenceListener::new(listener));
+            }
         }
-        let kv_engine =
-            engine_rocks::util::new_engine_opt(path.to_str().unwrap(), db_opts, cf_opts);
+        let kv_engine = if read_only {
+            self.open_cf_read_only(path, db_opts, cf_opts)
+        } else {
+            engine_rocks::util::new_engine_opt(path.to_str().unwrap(), db_opts, cf_opts)
+        };
         if let Err(e) = &kv_engine {
-            error!("failed to create tablet"; "id" => ctx.id, "suffix" => ?ctx.suffix, "path" => %path.display(), "err" => ?e);
-        } else if let Some(listener) = &self.inner.flow_listener && let Some(suffix) = ctx.suffix {
+            error!("failed to open tablet"; "id" => ctx.id, "suffix" => ?ctx.suffix,
+                "read_only" => read_only, "path" => %path.display(), "err" => ?e);
+        } else if !read_only && let Some(listener) = &self.inner.flow_listener && let Some(suffix) = ctx.suffix {
             listener.clone_with(ctx.id, suffix).on_created();
         }
         kv_engine
     }
 
+    /// Opens `path` read-only straight through the raw RocksDB binding,
+    /// rather than `engine_rocks::util`, which only exposes write-oriented
+    /// openers. RocksDB requires a distinct entry point for read-only
+    /// access, so this builds the same `ColumnFamilyDescriptor`s the write
+    /// path would register (from the already-resolved `db_opts`/`cf_opts`)
+    /// and opens the DB directly with them, matching the CF layout and
+    /// comparators a concurrent writer on the same path has open.
+    fn open_cf_read_only(
+        &self,
+        path: &Path,
+        db_opts: RocksDbOptions,
+        cf_opts: Vec<(&str, RocksCfOptions)>,
+    ) -> Result<RocksEngine> {
+        let cf_descs: Vec<ColumnFamilyDescriptor> = cf_opts
+            .into_iter()
+            .map(|(name, opt)| ColumnFamilyDescriptor::new(name, opt.into_raw()))
+            .collect();
+        let db = DB::open_cf_descriptors_read_only(
+            &db_opts.into_raw(),
+            path.to_str().unwrap(),
+            cf_descs,
+            false,
+        )
+        .map_err(|e| engine_traits::Error::Other(box_err!(e)))?;
+        Ok(RocksEngine::from_db(Arc::new(db)))
+    }
+
+    /// Opens a tablet through RocksDB's read-only mode, so backup, debug,
+    /// and ingest-verification tools can attach to a tablet directory that
+    /// is already opened (with the exclusive LOCK held) by the serving
+    /// process. Any write attempt against the returned engine is rejected
+    /// by RocksDB itself.
+    pub fn open_tablet_read_only(&self, ctx: TabletContext, path: &Path) -> Result<RocksEngine> {
+        self.open_tablet_impl(ctx, path, true)
+    }
+}
+
+impl TabletFactory<RocksEngine> for KvEngineFactory {
+    fn open_tablet(&self, ctx: TabletContext, path: &Path) -> Result<RocksEngine> {
+        self.open_tablet_impl(ctx, path, false)
+    }
+
     fn destroy_tablet(&self, ctx: TabletContext, path: &Path) -> Result<()> {
         info!("destroy tablet"; "path" => %path.display(), "id" => ctx.id, "suffix" => ?ctx.suffix);
-        // Create kv engine.
-        let _db_opts = self.db_opts();
-        let _cf_opts = self.cf_opts(EngineType::RaftKv2);
-        // TODOTODO: call rust-rocks or tirocks to destroy_engine;
-        // engine_rocks::util::destroy_engine(
-        //   path.to_str().unwrap(),
-        //   kv_db_opts,
-        //   kv_cfs_opts,
-        // )?;
-        let _ = std::fs::remove_dir_all(path);
+        // Reconstruct the same options the tablet was opened with, so every
+        // registered CF and configured `db_paths` directory is enumerated
+        // and destroyed, not just the tablet's own directory.
+        let kv_db_opts = self.db_opts(path, false);
+        let kv_cfs_opts = self.cf_opts(EngineType::RaftKv2);
+        match engine_rocks::util::destroy_engine(path.to_str().unwrap(), kv_db_opts, kv_cfs_opts) {
+            Ok(()) => {}
+            Err(e) => {
+                // Any error from the engine-level destroy falls back to a
+                // plain recursive delete, whatever its cause (e.g. `path`
+                // isn't, or is no longer, a valid RocksDB directory). Neither
+                // that error nor one from the recursive delete is returned.
+                warn!("engine-level destroy failed, falling back to remove_dir_all";
+                    "path" => %path.display(), "id" => ctx.id, "err" => ?e);
+                let _ = std::fs::remove_dir_all(path);
+            }
+        }
         if let Some(listener) = &self.inner.flow_listener && let Some(suffix) = ctx.suffix {
             listener.clone_with(ctx.id, suffix).on_destroyed();
         }
@@ -243,7 +413,7 @@ impl TabletFactory<RocksEngine> for KvEngineFactory {
 mod tests {
     use std::path::Path;
 
-    use engine_traits::TabletRegistry;
+    use engine_traits::{SyncMutable, TabletRegistry};
 
     use super::*;
     use crate::config::TikvConfig;
@@ -284,4 +454,148 @@ mod tests {
             .unwrap();
         assert!(!reg.tablet_factory().exists(&path));
     }
+
+    #[test]
+    fn test_resolve_db_paths() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let common_test_cfg = manifest_dir.join("components/test_raftstore/src/common-test.toml");
+        let cfg = TikvConfig::from_file(&common_test_cfg, None).unwrap_or_else(|e| {
+            panic!(
+                "invalid auto generated configuration file {}, err {}",
+                manifest_dir.display(),
+                e
+            );
+        });
+        let cache = cfg.storage.block_cache.build_shared_cache();
+        let env = cfg.build_shared_rocks_env(None, None).unwrap();
+        let factory = KvEngineFactoryBuilder::new(env, &cfg, cache)
+            .db_paths(vec![
+                (PathBuf::from("/tier0"), 100),
+                (PathBuf::from("/tier1"), 200),
+            ])
+            .build();
+
+        let resolved = factory.resolve_db_paths(Path::new("/data/db/3"));
+        assert_eq!(
+            resolved,
+            vec![
+                (PathBuf::from("/tier0/3"), 100),
+                (PathBuf::from("/tier1/3"), 200),
+            ]
+        );
+
+        // No db_paths configured: nothing to resolve against.
+        let factory = KvEngineFactoryBuilder::new(
+            cfg.build_shared_rocks_env(None, None).unwrap(),
+            &cfg,
+            cfg.storage.block_cache.build_shared_cache(),
+        )
+        .build();
+        assert!(factory.resolve_db_paths(Path::new("/data/db/3")).is_empty());
+    }
+
+    #[test]
+    fn test_engine_factory_destroy_tablet_fallback() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let common_test_cfg = manifest_dir.join("components/test_raftstore/src/common-test.toml");
+        let cfg = TikvConfig::from_file(&common_test_cfg, None).unwrap_or_else(|e| {
+            panic!(
+                "invalid auto generated configuration file {}, err {}",
+                manifest_dir.display(),
+                e
+            );
+        });
+        let cache = cfg.storage.block_cache.build_shared_cache();
+        let dir = test_util::temp_dir("test-engine-factory-destroy-fallback", false);
+        let env = cfg.build_shared_rocks_env(None, None).unwrap();
+
+        let factory = KvEngineFactoryBuilder::new(env, &cfg, cache).build();
+        let reg = TabletRegistry::new(Box::new(factory), dir.path()).unwrap();
+        let path = reg.tablet_path(1, 3);
+        // A directory that was never a RocksDB instance, so
+        // engine_rocks::util::destroy_engine fails and destroy_tablet must
+        // fall back to a plain recursive delete instead of leaving it behind.
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("not_a_db_file"), b"garbage").unwrap();
+
+        let mut tablet_ctx = TabletContext::with_infinite_region(1, Some(3));
+        tablet_ctx.suffix = Some(3);
+        reg.tablet_factory()
+            .destroy_tablet(tablet_ctx, &path)
+            .unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_engine_factory_read_only() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let common_test_cfg = manifest_dir.join("components/test_raftstore/src/common-test.toml");
+        let cfg = TikvConfig::from_file(&common_test_cfg, None).unwrap_or_else(|e| {
+            panic!(
+                "invalid auto generated configuration file {}, err {}",
+                manifest_dir.display(),
+                e
+            );
+        });
+        let cache = cfg.storage.block_cache.build_shared_cache();
+        let dir = test_util::temp_dir("test-engine-factory-read-only", false);
+        let env = cfg.build_shared_rocks_env(None, None).unwrap();
+
+        let factory = KvEngineFactoryBuilder::new(env, &cfg, cache).build();
+        let reg = TabletRegistry::new(Box::new(factory.clone()), dir.path()).unwrap();
+        let path = reg.tablet_path(1, 3);
+        let tablet_ctx = TabletContext::with_infinite_region(1, Some(3));
+        // The writer keeps the tablet open (and its LOCK file held) for the
+        // whole test, exactly the scenario open_tablet_read_only exists for.
+        let writer = reg
+            .tablet_factory()
+            .open_tablet(tablet_ctx.clone(), &path)
+            .unwrap();
+
+        // `open_tablet_read_only` is a `KvEngineFactory` method, not part of
+        // the `TabletFactory` trait that `reg.tablet_factory()` exposes.
+        let reader = factory.open_tablet_read_only(tablet_ctx, &path).unwrap();
+        reader.put(b"k", b"v").unwrap_err();
+
+        drop(reader);
+        drop(writer);
+    }
+
+    #[test]
+    fn test_engine_factory_memory_backend() {
+        let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let common_test_cfg = manifest_dir.join("components/test_raftstore/src/common-test.toml");
+        let cfg = TikvConfig::from_file(&common_test_cfg, None).unwrap_or_else(|e| {
+            panic!(
+                "invalid auto generated configuration file {}, err {}",
+                manifest_dir.display(),
+                e
+            );
+        });
+        let cache = cfg.storage.block_cache.build_shared_cache();
+        let dir = test_util::temp_dir("test-engine-factory-memory-backend", false);
+        let env = cfg.build_shared_rocks_env(None, None).unwrap();
+
+        let factory = KvEngineFactoryBuilder::new(env, &cfg, cache)
+            .backend(EngineBackend::Memory)
+            .build();
+        let reg = TabletRegistry::new(Box::new(factory), dir.path()).unwrap();
+        let path = reg.tablet_path(1, 3);
+        let mut tablet_ctx = TabletContext::with_infinite_region(1, Some(3));
+        let engine = reg
+            .tablet_factory()
+            .open_tablet(tablet_ctx.clone(), &path)
+            .unwrap();
+        engine.put(b"k", b"v").unwrap();
+
+        // The Memory backend never touches disk: nothing should have been
+        // created under the registry's own root directory.
+        assert!(std::fs::read_dir(dir.path()).unwrap().next().is_none());
+
+        drop(engine);
+        tablet_ctx.suffix = Some(3);
+        reg.tablet_factory()
+            .destroy_tablet(tablet_ctx, &path)
+            .unwrap();
+    }
 }